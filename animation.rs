@@ -0,0 +1,389 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compositor-thread transform animation. A `CommonLayer` can carry an `AnimationState`
+//! describing a timeline of keyframes; calling `tick_subtree` on the layer tree each
+//! frame advances every running animation and writes the interpolated transform, instead
+//! of requiring the embedder to recompute matrices itself.
+
+use layers::{Layer, ContainerLayerKind, CompositorLayerKind, TextureLayerKind};
+use layers::SolidColorLayerKind;
+
+use geom::matrix::Matrix4;
+
+/// An easing function controlling how the interpolation parameter advances between two
+/// keyframes.
+#[deriving(Clone)]
+pub enum TimingFunction {
+    Linear,
+    /// A cubic Bézier timing function given by its two control points; the curve's
+    /// endpoints are implicitly (0, 0) and (1, 1), matching CSS's `cubic-bezier()`.
+    CubicBezier(f64, f64, f64, f64),
+}
+
+impl TimingFunction {
+    pub fn ease_in() -> TimingFunction {
+        CubicBezier(0.42, 0.0, 1.0, 1.0)
+    }
+
+    pub fn ease_out() -> TimingFunction {
+        CubicBezier(0.0, 0.0, 0.58, 1.0)
+    }
+
+    /// Maps a linear interpolation parameter `t` in `[0, 1]` to an eased one.
+    pub fn apply(&self, t: f64) -> f64 {
+        match *self {
+            Linear => t,
+            CubicBezier(x1, y1, x2, y2) => solve_cubic_bezier(x1, y1, x2, y2, t),
+        }
+    }
+}
+
+/// Solves a CSS-style cubic Bézier timing function for `y`, given `x = t`, by binary
+/// searching the curve's parametric variable for the point whose x-coordinate matches.
+fn solve_cubic_bezier(x1: f64, y1: f64, x2: f64, y2: f64, t: f64) -> f64 {
+    fn bezier(p1: f64, p2: f64, u: f64) -> f64 {
+        let v = 1.0 - u;
+        3.0 * v * v * u * p1 + 3.0 * v * u * u * p2 + u * u * u
+    }
+
+    let (mut lo, mut hi) = (0.0f64, 1.0f64);
+    let mut u = t;
+    for _ in range(0u, 20) {
+        u = (lo + hi) / 2.0;
+        let x = bezier(x1, x2, u);
+        if (x - t).abs() < 1.0e-6 {
+            break;
+        }
+        if x < t { lo = u } else { hi = u }
+    }
+    bezier(y1, y2, u)
+}
+
+/// One point on a layer's transform timeline.
+#[deriving(Clone)]
+pub struct Keyframe {
+    /// This keyframe's position on the timeline, in the same units as `tick`'s `now`.
+    pub time: f64,
+    pub transform: Matrix4<f32>,
+    /// The easing applied between this keyframe and the next one.
+    pub timing_function: TimingFunction,
+}
+
+pub fn Keyframe(time: f64, transform: Matrix4<f32>, timing_function: TimingFunction) -> Keyframe {
+    Keyframe {
+        time: time,
+        transform: transform,
+        timing_function: timing_function,
+    }
+}
+
+/// Whether alternating iterations of the timeline run in reverse.
+#[deriving(Eq, Clone)]
+pub enum AnimationDirection {
+    Normal,
+    Alternate,
+}
+
+/// Drives a single layer's transform across a timeline of keyframes.
+pub struct AnimationState {
+    pub keyframes: Vec<Keyframe>,
+    /// The time, in the same units as `tick`'s `now`, at which the animation begins.
+    pub start_time: f64,
+    /// How long one iteration of the timeline lasts.
+    pub duration: f64,
+    /// How many iterations to play; `None` means loop forever.
+    pub iteration_count: Option<uint>,
+    pub direction: AnimationDirection,
+}
+
+impl AnimationState {
+    pub fn new(keyframes: Vec<Keyframe>,
+               start_time: f64,
+               duration: f64,
+               iteration_count: Option<uint>,
+               direction: AnimationDirection)
+               -> AnimationState {
+        assert!(keyframes.len() >= 2, "an animation needs at least two keyframes");
+        AnimationState {
+            keyframes: keyframes,
+            start_time: start_time,
+            duration: duration,
+            iteration_count: iteration_count,
+            direction: direction,
+        }
+    }
+
+    /// Returns true if `now` still falls within this animation's run.
+    pub fn is_animating(&self, now: f64) -> bool {
+        match self.iteration_count {
+            None => true,
+            Some(count) => now < self.start_time + self.duration * count as f64,
+        }
+    }
+
+    /// Computes the interpolated transform for `now`, or `None` once the animation has
+    /// played out its `iteration_count`.
+    pub fn sample(&self, now: f64) -> Option<Matrix4<f32>> {
+        if !self.is_animating(now) {
+            return None;
+        }
+
+        let elapsed = now - self.start_time;
+        let iteration = (elapsed / self.duration).floor() as uint;
+        let mut local_time = elapsed % self.duration;
+        if self.direction == Alternate && iteration % 2 == 1 {
+            local_time = self.duration - local_time;
+        }
+
+        let (start, end) = self.bracketing_keyframes(local_time);
+        let span = end.time - start.time;
+        let raw_t = if span > 0.0 { (local_time - start.time) / span } else { 1.0 };
+        let t = start.timing_function.apply(raw_t.max(0.0).min(1.0));
+        Some(interpolate_transform(&start.transform, &end.transform, t as f32))
+    }
+
+    fn bracketing_keyframes<'a>(&'a self, local_time: f64) -> (&'a Keyframe, &'a Keyframe) {
+        let mut i = 0u;
+        while i < self.keyframes.len() - 2 && self.keyframes[i + 1].time <= local_time {
+            i += 1;
+        }
+        (&self.keyframes[i], &self.keyframes[i + 1])
+    }
+}
+
+/// A unit quaternion, used to interpolate rotation independently of translation and
+/// scale during matrix decomposition.
+struct Quaternion {
+    x: f32,
+    y: f32,
+    z: f32,
+    w: f32,
+}
+
+impl Quaternion {
+    /// Spherical linear interpolation between `self` and `other`.
+    fn slerp(&self, other: &Quaternion, t: f32) -> Quaternion {
+        let mut cos_half_theta = self.x * other.x + self.y * other.y +
+                                  self.z * other.z + self.w * other.w;
+
+        let other = if cos_half_theta < 0.0 {
+            cos_half_theta = -cos_half_theta;
+            Quaternion { x: -other.x, y: -other.y, z: -other.z, w: -other.w }
+        } else {
+            Quaternion { x: other.x, y: other.y, z: other.z, w: other.w }
+        };
+
+        if cos_half_theta > 0.9999 {
+            return Quaternion {
+                x: self.x + (other.x - self.x) * t,
+                y: self.y + (other.y - self.y) * t,
+                z: self.z + (other.z - self.z) * t,
+                w: self.w + (other.w - self.w) * t,
+            };
+        }
+
+        let half_theta = cos_half_theta.acos();
+        let sin_half_theta = (1.0 - cos_half_theta * cos_half_theta).sqrt();
+        let ratio_a = ((1.0 - t) * half_theta).sin() / sin_half_theta;
+        let ratio_b = (t * half_theta).sin() / sin_half_theta;
+
+        Quaternion {
+            x: self.x * ratio_a + other.x * ratio_b,
+            y: self.y * ratio_a + other.y * ratio_b,
+            z: self.z * ratio_a + other.z * ratio_b,
+            w: self.w * ratio_a + other.w * ratio_b,
+        }
+    }
+
+    /// Recomposes this quaternion into the upper 3x3 rotation block of a `Matrix4`.
+    fn to_matrix(&self) -> Matrix4<f32> {
+        let (x, y, z, w) = (self.x, self.y, self.z, self.w);
+        Matrix4::new(1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + z * w), 2.0 * (x * z - y * w), 0.0,
+                      2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + x * w), 0.0,
+                      2.0 * (x * z + y * w), 2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y), 0.0,
+                      0.0, 0.0, 0.0, 1.0)
+    }
+}
+
+/// The translation, scale, and rotation pulled out of a `Matrix4`, so each component can
+/// be interpolated with the operation that makes sense for it.
+struct DecomposedMatrix {
+    translation: (f32, f32, f32),
+    scale: (f32, f32, f32),
+    rotation: Quaternion,
+}
+
+fn decompose(matrix: &Matrix4<f32>) -> DecomposedMatrix {
+    let translation = (matrix.m41, matrix.m42, matrix.m43);
+
+    let sx = (matrix.m11 * matrix.m11 + matrix.m12 * matrix.m12 + matrix.m13 * matrix.m13).sqrt();
+    let sy = (matrix.m21 * matrix.m21 + matrix.m22 * matrix.m22 + matrix.m23 * matrix.m23).sqrt();
+    let sz = (matrix.m31 * matrix.m31 + matrix.m32 * matrix.m32 + matrix.m33 * matrix.m33).sqrt();
+
+    let (r11, r12, r13) = (matrix.m11 / sx, matrix.m12 / sx, matrix.m13 / sx);
+    let (r21, r22, r23) = (matrix.m21 / sy, matrix.m22 / sy, matrix.m23 / sy);
+    let (r31, r32, r33) = (matrix.m31 / sz, matrix.m32 / sz, matrix.m33 / sz);
+
+    let trace = r11 + r22 + r33;
+    let rotation = if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        Quaternion {
+            x: (r23 - r32) / s,
+            y: (r31 - r13) / s,
+            z: (r12 - r21) / s,
+            w: 0.25 * s,
+        }
+    } else if r11 > r22 && r11 > r33 {
+        let s = (1.0 + r11 - r22 - r33).sqrt() * 2.0;
+        Quaternion { x: 0.25 * s, y: (r21 + r12) / s, z: (r31 + r13) / s, w: (r23 - r32) / s }
+    } else if r22 > r33 {
+        let s = (1.0 + r22 - r11 - r33).sqrt() * 2.0;
+        Quaternion { x: (r21 + r12) / s, y: 0.25 * s, z: (r32 + r23) / s, w: (r31 - r13) / s }
+    } else {
+        let s = (1.0 + r33 - r11 - r22).sqrt() * 2.0;
+        Quaternion { x: (r31 + r13) / s, y: (r32 + r23) / s, z: 0.25 * s, w: (r12 - r21) / s }
+    };
+
+    DecomposedMatrix {
+        translation: translation,
+        scale: (sx, sy, sz),
+        rotation: rotation,
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Decomposes `start` and `end` into translation/scale/rotation, interpolates each
+/// component with the operation suited to it (lerp for translation and scale, slerp for
+/// rotation), and recomposes the result.
+fn interpolate_transform(start: &Matrix4<f32>, end: &Matrix4<f32>, t: f32) -> Matrix4<f32> {
+    let start = decompose(start);
+    let end = decompose(end);
+
+    let translation = (lerp(start.translation.val0(), end.translation.val0(), t),
+                        lerp(start.translation.val1(), end.translation.val1(), t),
+                        lerp(start.translation.val2(), end.translation.val2(), t));
+    let scale = (lerp(start.scale.val0(), end.scale.val0(), t),
+                 lerp(start.scale.val1(), end.scale.val1(), t),
+                 lerp(start.scale.val2(), end.scale.val2(), t));
+    let rotation = start.rotation.slerp(&end.rotation, t);
+
+    let mut result = rotation.to_matrix();
+    result.m11 = result.m11 * scale.val0();
+    result.m12 = result.m12 * scale.val0();
+    result.m13 = result.m13 * scale.val0();
+    result.m21 = result.m21 * scale.val1();
+    result.m22 = result.m22 * scale.val1();
+    result.m23 = result.m23 * scale.val1();
+    result.m31 = result.m31 * scale.val2();
+    result.m32 = result.m32 * scale.val2();
+    result.m33 = result.m33 * scale.val2();
+    result.m41 = translation.val0();
+    result.m42 = translation.val1();
+    result.m43 = translation.val2();
+    result
+}
+
+/// Advances every running animation in `layer`'s subtree to `now`, writing interpolated
+/// transforms back via `CommonLayer::set_transform`. Returns true if any layer in the
+/// subtree is still animating, so the compositor knows whether to schedule another frame.
+pub fn tick_subtree(layer: &Layer, now: f64) -> bool {
+    let mut still_animating = layer.with_common(|common| {
+        let sampled = match common.animation {
+            Some(ref state) => state.sample(now),
+            None => None,
+        };
+        match sampled {
+            Some(transform) => {
+                common.set_transform(transform);
+                true
+            }
+            None => false,
+        }
+    });
+
+    match *layer {
+        ContainerLayerKind(ref container) => {
+            for child in container.children() {
+                still_animating = tick_subtree(&child, now) || still_animating;
+            }
+        }
+        CompositorLayerKind(ref compositor) => {
+            for child in compositor.container_layer.children() {
+                still_animating = tick_subtree(&child, now) || still_animating;
+            }
+        }
+        TextureLayerKind(..) | SolidColorLayerKind(..) => {}
+    }
+
+    still_animating
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Quaternion, Linear, CubicBezier, interpolate_transform};
+
+    use geom::matrix::Matrix4;
+
+    fn quaternion_from_axis_angle(axis: (f32, f32, f32), angle: f32) -> Quaternion {
+        let (x, y, z) = axis;
+        let len = (x * x + y * y + z * z).sqrt();
+        let half = angle / 2.0;
+        let s = half.sin();
+        Quaternion { x: x / len * s, y: y / len * s, z: z / len * s, w: half.cos() }
+    }
+
+    fn compose(rotation: &Quaternion, scale: (f32, f32, f32), translation: (f32, f32, f32)) -> Matrix4<f32> {
+        let mut m = rotation.to_matrix();
+        m.m11 = m.m11 * scale.val0(); m.m12 = m.m12 * scale.val0(); m.m13 = m.m13 * scale.val0();
+        m.m21 = m.m21 * scale.val1(); m.m22 = m.m22 * scale.val1(); m.m23 = m.m23 * scale.val1();
+        m.m31 = m.m31 * scale.val2(); m.m32 = m.m32 * scale.val2(); m.m33 = m.m33 * scale.val2();
+        m.m41 = translation.val0(); m.m42 = translation.val1(); m.m43 = translation.val2();
+        m
+    }
+
+    fn assert_matrices_approx_eq(a: &Matrix4<f32>, b: &Matrix4<f32>) {
+        let epsilon = 1.0e-3;
+        let fields = [(a.m11, b.m11), (a.m12, b.m12), (a.m13, b.m13), (a.m14, b.m14),
+                      (a.m21, b.m21), (a.m22, b.m22), (a.m23, b.m23), (a.m24, b.m24),
+                      (a.m31, b.m31), (a.m32, b.m32), (a.m33, b.m33), (a.m34, b.m34),
+                      (a.m41, b.m41), (a.m42, b.m42), (a.m43, b.m43), (a.m44, b.m44)];
+        for &(x, y) in fields.iter() {
+            assert!((x - y).abs() < epsilon, "expected {} ~= {}", x, y);
+        }
+    }
+
+    #[test]
+    fn interpolate_transform_round_trips_at_endpoints() {
+        let start = compose(&quaternion_from_axis_angle((0.0, 0.0, 1.0), 0.3),
+                             (1.0, 2.0, 1.5), (10.0, -5.0, 0.0));
+        let end = compose(&quaternion_from_axis_angle((1.0, 1.0, 0.0), 1.1),
+                           (0.5, 0.75, 2.0), (-3.0, 8.0, 4.0));
+
+        assert_matrices_approx_eq(&interpolate_transform(&start, &end, 0.0), &start);
+        assert_matrices_approx_eq(&interpolate_transform(&start, &end, 1.0), &end);
+    }
+
+    #[test]
+    fn linear_timing_is_the_identity_at_the_midpoint() {
+        assert!((Linear.apply(0.5) - 0.5).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn symmetric_cubic_bezier_passes_through_its_own_midpoint() {
+        // CSS's ease-in-out, (0.42, 0, 0.58, 1), is point-symmetric about (0.5, 0.5) —
+        // its control points satisfy x2 = 1 - x1 and y2 = 1 - y1 — so it must map t=0.5
+        // to y=0.5 exactly, regardless of the curve's shape elsewhere.
+        let ease_in_out = CubicBezier(0.42, 0.0, 0.58, 1.0);
+        assert!((ease_in_out.apply(0.5) - 0.5).abs() < 1.0e-3);
+    }
+}
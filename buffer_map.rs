@@ -0,0 +1,173 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A pool of recyclable tiles, bucketed by size, so the render task can hand back
+//! spent buffers instead of destroying and reallocating native surfaces every frame.
+
+use tile::Tile;
+use platform::surface::NativePaintingGraphicsContext;
+
+use geom::size::Size2D;
+use std::collections::HashMap;
+
+/// A key used to bucket buffers of matching size together.
+#[deriving(Eq, Hash, Clone, Copy)]
+struct BufferKey(uint, uint);
+
+impl BufferKey {
+    fn get(size: Size2D<uint>) -> BufferKey {
+        BufferKey(size.width, size.height)
+    }
+}
+
+struct BufferValue<T> {
+    buffers: Vec<T>,
+    /// The value of `BufferMap::counter` the last time this bucket was inserted into.
+    /// Used to pick the least-recently-inserted bucket for eviction.
+    mark: uint,
+}
+
+impl<T> BufferValue<T> {
+    fn new() -> BufferValue<T> {
+        BufferValue {
+            buffers: vec!(),
+            mark: 0,
+        }
+    }
+}
+
+/// A memory-bounded cache of `Tile`s, keyed by size. `insert` stores a fresh batch of
+/// buffers and evicts the oldest buckets until `mem` is back under `max_mem`; `find`
+/// pulls a matching-size buffer back out for reuse.
+pub struct BufferMap<T> {
+    map: HashMap<BufferKey, BufferValue<T>>,
+    /// The total memory used by all buffers currently in the map.
+    mem: uint,
+    /// The maximum amount of memory this map is allowed to hold onto.
+    max_mem: uint,
+    /// Incremented on every `insert` call; stamped onto the touched bucket.
+    counter: uint,
+}
+
+impl<T: Tile> BufferMap<T> {
+    pub fn new(max_mem: uint) -> BufferMap<T> {
+        BufferMap {
+            map: HashMap::new(),
+            mem: 0,
+            max_mem: max_mem,
+            counter: 0,
+        }
+    }
+
+    /// Inserts a batch of buffers into the map, then evicts the least-recently-inserted
+    /// buckets (via `Tile::destroy`) until total memory usage is back within budget.
+    pub fn insert(&mut self, graphics_context: &NativePaintingGraphicsContext, buffers: Vec<T>) {
+        let counter = self.counter;
+        for buffer in buffers.move_iter() {
+            self.mem += buffer.get_mem();
+            let key = BufferKey::get(buffer.get_size_2d());
+            let value = self.map.find_or_insert_with(key, |_| BufferValue::new());
+            value.buffers.push(buffer);
+            value.mark = counter;
+        }
+        self.counter += 1;
+
+        while self.mem > self.max_mem {
+            let oldest = self.map.iter().min_by(|&(_, value)| value.mark).map(|(key, _)| *key);
+            match oldest {
+                Some(key) => {
+                    let value = self.map.pop(&key).unwrap();
+                    for buffer in value.buffers.move_iter() {
+                        self.mem -= buffer.get_mem();
+                        buffer.destroy(graphics_context);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Removes and returns a buffer matching the requested size, if one is cached.
+    pub fn find(&mut self, size: Size2D<uint>) -> Option<T> {
+        let key = BufferKey::get(size);
+        let (result, is_empty) = match self.map.find_mut(&key) {
+            Some(value) => {
+                let result = value.buffers.pop();
+                if result.is_some() {
+                    self.mem -= result.get_ref().get_mem();
+                }
+                (result, value.buffers.is_empty())
+            }
+            None => return None,
+        };
+
+        if is_empty {
+            self.map.remove(&key);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BufferMap;
+    use tile::Tile;
+    use platform::surface::NativePaintingGraphicsContext;
+
+    use geom::size::Size2D;
+
+    struct MockTile {
+        size: Size2D<uint>,
+    }
+
+    impl Tile for MockTile {
+        fn get_mem(&self) -> uint {
+            self.size.width * self.size.height
+        }
+        fn is_valid(&self, _scale: f32) -> bool {
+            true
+        }
+        fn get_size_2d(&self) -> Size2D<uint> {
+            self.size
+        }
+        fn mark_wont_leak(&mut self) {}
+        fn destroy(self, _graphics_context: &NativePaintingGraphicsContext) {}
+    }
+
+    fn tile(width: uint, height: uint) -> MockTile {
+        MockTile { size: Size2D(width, height) }
+    }
+
+    // The platform surface backend isn't part of this tree; `insert` only needs a
+    // context to hand to `Tile::destroy`, which `MockTile` ignores.
+    fn mock_graphics_context() -> NativePaintingGraphicsContext {
+        unsafe { ::std::mem::zeroed() }
+    }
+
+    #[test]
+    fn find_returns_none_for_a_size_never_inserted() {
+        let mut map: BufferMap<MockTile> = BufferMap::new(1000);
+        assert!(map.find(Size2D(4u, 4u)).is_none());
+    }
+
+    #[test]
+    fn insert_evicts_the_least_recently_touched_bucket_once_over_budget() {
+        let context = mock_graphics_context();
+        // 10x10 = 100 "bytes", 10x5 = 50; a budget of 120 holds one but not both.
+        let mut map: BufferMap<MockTile> = BufferMap::new(120);
+
+        map.insert(&context, vec!(tile(10, 10)));
+        map.insert(&context, vec!(tile(10, 5)));
+
+        // The 10x10 bucket was touched first, so it's the one evicted to get back
+        // under budget; the just-inserted 10x5 bucket survives.
+        assert!(map.find(Size2D(10u, 10u)).is_none());
+        assert!(map.find(Size2D(10u, 5u)).is_some());
+    }
+}
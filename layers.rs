@@ -10,6 +10,8 @@
 use texturegl::Texture;
 use quadtree::{Quadtree};
 use platform::surface::{NativeSurface, NativeSurfaceMethods};
+use util::BufferRequest;
+use animation::AnimationState;
 
 use geom::matrix::{Matrix4, identity};
 use geom::size::Size2D;
@@ -20,6 +22,7 @@ use std::cell::RefCell;
 use std::fmt::{Formatter, Result, Show};
 use std::rc::Rc;
 
+#[deriving(Clone)]
 pub enum Format {
     ARGB32Format,
     RGB24Format
@@ -39,6 +42,7 @@ pub enum Layer {
     ContainerLayerKind(Rc<ContainerLayer>),
     TextureLayerKind(Rc<TextureLayer>),
     CompositorLayerKind(Rc<CompositorLayer>),
+    SolidColorLayerKind(Rc<SolidColorLayer>),
 }
 
 impl Layer {
@@ -53,6 +57,9 @@ impl Layer {
             CompositorLayerKind(ref compositor_layer) => {
                 f(&mut *compositor_layer.container_layer.common.borrow_mut())
             },
+            SolidColorLayerKind(ref solid_color_layer) => {
+                f(&mut *solid_color_layer.common.borrow_mut())
+            },
 
         }
     }
@@ -64,6 +71,10 @@ pub struct CommonLayer {
     pub next_sibling: Option<Layer>,
 
     pub transform: Matrix4<f32>,
+
+    /// The running transform animation attached to this layer, if any. Advanced by
+    /// calling `animation::tick_subtree` on the layer tree each frame.
+    pub animation: Option<AnimationState>,
 }
 
 impl CommonLayer {
@@ -79,6 +90,7 @@ pub fn CommonLayer() -> CommonLayer {
         prev_sibling: None,
         next_sibling: None,
         transform: identity(),
+        animation: None,
     }
 }
 
@@ -245,15 +257,40 @@ pub struct TextureLayer {
     size: Size2D<uint>,
     /// Whether this texture is flipped vertically.
     pub flip: Flip,
+    /// The pixel format the texture was uploaded in, so a future upload can request the
+    /// matching `pixels::convert` instead of assuming one layout.
+    pub format: Format,
 }
 
 impl TextureLayer {
-    pub fn new(texture: Texture, size: Size2D<uint>, flip: Flip) -> TextureLayer {
+    pub fn new(texture: Texture, size: Size2D<uint>, flip: Flip, format: Format) -> TextureLayer {
         TextureLayer {
             common: RefCell::new(CommonLayer()),
             texture: texture,
             size: size,
             flip: flip,
+            format: format,
+        }
+    }
+}
+
+/// A layer that composites as a single flat-color quad instead of a texture upload.
+/// Useful for the page background and scrolled-past voids, where allocating and
+/// uploading a tile full of one color would be wasted work.
+pub struct SolidColorLayer {
+    pub common: RefCell<CommonLayer>,
+    /// The color this layer should composite as.
+    pub color: Color,
+    /// The bounds of this layer, in page coordinates.
+    pub bounds: Rect<f32>,
+}
+
+impl SolidColorLayer {
+    pub fn new(color: Color, bounds: Rect<f32>) -> SolidColorLayer {
+        SolidColorLayer {
+            common: RefCell::new(CommonLayer()),
+            color: color,
+            bounds: bounds,
         }
     }
 }
@@ -318,7 +355,11 @@ pub struct LayerBuffer {
     pub stride: uint,
 
     /// Used by the RenderTask to route buffers to the correct graphics context for recycling
-    pub render_idx: uint
+    pub render_idx: uint,
+
+    /// The pixel format the buffer's bytes are stored in, so an upload can request the
+    /// matching `pixels::convert` instead of assuming one layout.
+    pub format: Format,
 }
 
 /// A set of layer buffers. This is an atomic unit used to switch between the front and back
@@ -345,7 +386,9 @@ pub struct CompositorLayer {
     pub id: LayerId,
 
     /// This layer's quadtree. This is where all buffers are stored for this layer.
-    pub quadtree: MaybeQuadtree,
+    /// Behind a `RefCell` for the same reason as `scroll_offset` below: a
+    /// `CompositorLayer` is only ever reached through a shared `Rc`.
+    pub quadtree: RefCell<MaybeQuadtree>,
 
     /// The size of the underlying page in page coordinates. This is an option
     /// because we may not know the size of the page until layout is finished completely.
@@ -353,8 +396,9 @@ pub struct CompositorLayer {
     pub page_size: Option<Size2D<f32>>,
 
     /// The offset of the page due to scrolling. (0,0) is when the window sees the
-    /// top left corner of the page.
-    pub scroll_offset: TypedPoint2D<PagePx, f32>,
+    /// top left corner of the page. Behind a `RefCell` because scrolling reaches a
+    /// layer through a shared `Rc<CompositorLayer>` (see `scroll::handle_scroll`).
+    pub scroll_offset: RefCell<TypedPoint2D<PagePx, f32>>,
 
     /// This layer's quadtree. This is where all buffers are stored for this layer.
     //pub quadtree: MaybeQuadtree,
@@ -395,3 +439,29 @@ impl MaybeQuadtree {
         }
     }
 }
+
+impl CompositorLayer {
+    /// Marks every tile that intersects `dirty_rect`, given in page coordinates, as
+    /// invalid. This flips the `valid` flag `quadtree` keeps alongside each stored tile
+    /// (see `quadtree::QuadtreeTile`), so the next call to `get_buffer_requests` for an
+    /// overlapping window will re-request those tiles even if their resolution still
+    /// matches.
+    pub fn invalidate_rect(&self, dirty_rect: Rect<f32>) {
+        match *self.quadtree.borrow_mut() {
+            Tree(ref mut quadtree) => quadtree.set_status_page(dirty_rect, false, true),
+            NoTree(..) => {} // Nothing has been rendered yet, so there is nothing to invalidate.
+        }
+    }
+
+    /// Returns the `BufferRequest`s needed to bring `window`, in page coordinates, fully
+    /// up to date at the given `scale`. A tile that falls within `window`, is still valid
+    /// at `scale` (see `Tile::is_valid`), and was not flagged dirty by `invalidate_rect` is
+    /// skipped, so a small content update yields a proportionally small request list
+    /// instead of a full-viewport repaint.
+    pub fn get_buffer_requests(&self, window: Rect<f32>, scale: f32) -> Vec<BufferRequest> {
+        match *self.quadtree.borrow_mut() {
+            Tree(ref mut quadtree) => quadtree.get_tile_rects_page(window, scale),
+            NoTree(..) => vec!(), // No page size yet, so there are no tiles to request.
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pixel-format conversion, keyed off the `Format` enum. `TextureLayer` and `LayerBuffer`
+//! each record the `Format` their bytes are stored in, so an upload can ask for exactly
+//! the conversion it needs instead of assuming one hardcoded layout.
+
+use layers::Format;
+use layers::{ARGB32Format, RGB24Format};
+
+/// Converts `src` from `from` to `to`. `width` is the number of real pixels per row;
+/// `stride` is the row pitch in pixels, like `LayerBuffer::stride`, and may be larger
+/// than `width` when the buffer is padded for alignment. Only the first `width` columns
+/// of each row are emitted, so padding never ends up in the result.
+pub fn convert(src: &[u8], from: Format, to: Format, width: uint, stride: uint) -> Vec<u8> {
+    match (from, to) {
+        (ARGB32Format, RGB24Format) => unpremultiply_and_swap(src, width, stride),
+        (RGB24Format, ARGB32Format) => swap_and_premultiply(src, width, stride),
+        (ARGB32Format, ARGB32Format) | (RGB24Format, RGB24Format) => {
+            if width == stride {
+                src.to_vec()
+            } else {
+                strip_padding(src, width, stride, bytes_per_pixel(from))
+            }
+        }
+    }
+}
+
+fn bytes_per_pixel(format: Format) -> uint {
+    match format {
+        ARGB32Format => 4,
+        RGB24Format => 3,
+    }
+}
+
+/// Copies `width` pixels out of every `stride`-pixel row, dropping the rest unchanged.
+fn strip_padding(src: &[u8], width: uint, stride: uint, bpp: uint) -> Vec<u8> {
+    let row_bytes = stride * bpp;
+    let rows = src.len() / row_bytes;
+    let mut dest = Vec::with_capacity(rows * width * bpp);
+    for row in range(0, rows) {
+        let row_start = row * row_bytes;
+        dest.push_all(src.slice(row_start, row_start + width * bpp));
+    }
+    dest
+}
+
+/// Un-premultiplies alpha and swaps 32bpp premultiplied BGRA to 24bpp straight RGB,
+/// dropping the alpha channel and any row padding past `width`.
+fn unpremultiply_and_swap(src: &[u8], width: uint, stride: uint) -> Vec<u8> {
+    let row_bytes = stride * 4;
+    let rows = src.len() / row_bytes;
+    let mut dest = Vec::with_capacity(rows * width * 3);
+    for row in range(0, rows) {
+        let row_start = row * row_bytes;
+        for col in range(0, width) {
+            let i = row_start + col * 4;
+            let (b, g, r, a) = (src[i], src[i + 1], src[i + 2], src[i + 3]);
+            let (r, g, b) = unpremultiply(r, g, b, a);
+            dest.push(r);
+            dest.push(g);
+            dest.push(b);
+        }
+    }
+    dest
+}
+
+/// Swaps 24bpp straight RGB to 32bpp premultiplied BGRA, with a fully opaque alpha
+/// channel (there is no alpha to premultiply against), dropping any row padding past
+/// `width`.
+fn swap_and_premultiply(src: &[u8], width: uint, stride: uint) -> Vec<u8> {
+    let row_bytes = stride * 3;
+    let rows = src.len() / row_bytes;
+    let mut dest = Vec::with_capacity(rows * width * 4);
+    for row in range(0, rows) {
+        let row_start = row * row_bytes;
+        for col in range(0, width) {
+            let i = row_start + col * 3;
+            let (r, g, b) = (src[i], src[i + 1], src[i + 2]);
+            dest.push(b);
+            dest.push(g);
+            dest.push(r);
+            dest.push(0xff);
+        }
+    }
+    dest
+}
+
+fn unpremultiply(r: u8, g: u8, b: u8, a: u8) -> (u8, u8, u8) {
+    if a == 0 {
+        return (0, 0, 0);
+    }
+    let a = a as u32;
+    (((r as u32 * 255) / a) as u8,
+     ((g as u32 * 255) / a) as u8,
+     ((b as u32 * 255) / a) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::convert;
+    use layers::{ARGB32Format, RGB24Format};
+
+    // Each row is `stride` (2) pixels wide but only the first `width` (1) pixel is real
+    // content; the second pixel in every row is padding that must not survive `convert`.
+
+    #[test]
+    fn argb32_to_rgb24_drops_padding_and_unswizzles() {
+        // Row: one opaque BGRA pixel (B=10, G=20, R=30), then a padding pixel.
+        let src = vec!(10u8, 20, 30, 255, 99, 99, 99, 99);
+        let out = convert(src.as_slice(), ARGB32Format, RGB24Format, 1, 2);
+        assert_eq!(out, vec!(30u8, 20, 10));
+    }
+
+    #[test]
+    fn rgb24_to_argb32_drops_padding_and_swizzles() {
+        // Row: one straight RGB pixel (R=1, G=2, B=3), then a padding pixel.
+        let src = vec!(1u8, 2, 3, 9, 9, 9);
+        let out = convert(src.as_slice(), RGB24Format, ARGB32Format, 1, 2);
+        assert_eq!(out, vec!(3u8, 2, 1, 255));
+    }
+
+    #[test]
+    fn argb32_to_argb32_drops_padding() {
+        let src = vec!(1u8, 2, 3, 4, 9, 9, 9, 9);
+        let out = convert(src.as_slice(), ARGB32Format, ARGB32Format, 1, 2);
+        assert_eq!(out, vec!(1u8, 2, 3, 4));
+    }
+
+    #[test]
+    fn rgb24_to_rgb24_drops_padding() {
+        let src = vec!(1u8, 2, 3, 9, 9, 9);
+        let out = convert(src.as_slice(), RGB24Format, RGB24Format, 1, 2);
+        assert_eq!(out, vec!(1u8, 2, 3));
+    }
+}
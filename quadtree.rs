@@ -0,0 +1,166 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Storage for a `CompositorLayer`'s tiles, indexed by row and column so a query for a
+//! `dirty_rect`/`window` only has to scan the grid cells it can possibly overlap, not
+//! every tile ever allocated for the page. Each slot also tracks a `valid` flag
+//! independently of the tile it holds, which is what lets
+//! `CompositorLayer::invalidate_rect` flag stale content without throwing the tile away.
+
+use tile::Tile;
+use util::BufferRequest;
+
+use geom::rect::Rect;
+use geom::point::Point2D;
+use geom::size::Size2D;
+
+struct QuadtreeTile<T> {
+    /// This tile's rect, in page coordinates. Fixed for the lifetime of the quadtree.
+    rect: Rect<f32>,
+    /// The tile currently painted for `rect`, if any has been uploaded yet.
+    tile: Option<T>,
+    /// False once `set_status_page` has flagged this tile's content as stale. A tile
+    /// starts invalid, since no content has been painted into it yet.
+    valid: bool,
+}
+
+/// A grid of fixed-size tiles covering a page, indexed by row and column so that
+/// locating the tiles under a rect is a lookup instead of a linear scan. Each tile
+/// carries its own validity flag (see module docs).
+pub struct Quadtree<T> {
+    /// `rows[row][col]` is the tile covering
+    /// `(col * max_tile_size, row * max_tile_size)`.
+    rows: Vec<Vec<QuadtreeTile<T>>>,
+    cols: uint,
+    pub max_tile_size: uint,
+}
+
+impl<T: Tile> Quadtree<T> {
+    pub fn new(page_size: Size2D<f32>, max_tile_size: uint) -> Quadtree<T> {
+        let tile_size = max_tile_size as f32;
+        let cols = (page_size.width / tile_size).ceil() as uint;
+        let num_rows = (page_size.height / tile_size).ceil() as uint;
+
+        let rows = Vec::from_fn(num_rows, |row| {
+            Vec::from_fn(cols, |col| {
+                QuadtreeTile {
+                    rect: Rect(Point2D(col as f32 * tile_size, row as f32 * tile_size),
+                               Size2D(tile_size, tile_size)),
+                    tile: None,
+                    valid: false,
+                }
+            })
+        });
+
+        Quadtree {
+            rows: rows,
+            cols: cols,
+            max_tile_size: max_tile_size,
+        }
+    }
+
+    /// Stores a freshly-painted tile for whichever slot covers `rect`, marking it valid.
+    pub fn add_tile(&mut self, rect: Rect<f32>, tile: T) {
+        self.for_each_slot_mut(rect, true, |slot| {
+            if slot.rect == rect {
+                slot.tile = Some(tile);
+                slot.valid = true;
+            }
+        });
+    }
+
+    /// Sets the `valid` flag on every tile intersecting `page_rect` (or merely touching
+    /// its border, if `include_border` is true). Only the rows/columns that can overlap
+    /// `page_rect` are visited.
+    pub fn set_status_page(&mut self, page_rect: Rect<f32>, valid: bool, include_border: bool) {
+        self.for_each_slot_mut(page_rect, include_border, |slot| {
+            slot.valid = valid;
+        });
+    }
+
+    /// Returns the `BufferRequest`s needed to bring `window` fully up to date at `scale`:
+    /// every tile overlapping `window` that is missing, flagged invalid, or no longer
+    /// matches `scale` (see `Tile::is_valid`). A tile that is present, valid, and still
+    /// the right scale is skipped. Only the rows/columns that can overlap `window` are
+    /// visited, so the scan cost tracks the size of `window`, not the whole page.
+    pub fn get_tile_rects_page(&self, window: Rect<f32>, scale: f32) -> Vec<BufferRequest> {
+        let mut requests = vec!();
+        let (row_range, col_range) = self.grid_range(window);
+        for row in range(row_range.0, row_range.1) {
+            for col in range(col_range.0, col_range.1) {
+                let slot = &self.rows[row][col];
+                if !rects_intersect(&slot.rect, &window, true) {
+                    continue;
+                }
+
+                let needs_repaint = match slot.tile {
+                    None => true,
+                    Some(ref tile) => !slot.valid || !tile.is_valid(scale),
+                };
+                if !needs_repaint {
+                    continue;
+                }
+
+                let screen_rect = Rect(
+                    Point2D((slot.rect.origin.x * scale) as uint, (slot.rect.origin.y * scale) as uint),
+                    Size2D((slot.rect.size.width * scale) as uint, (slot.rect.size.height * scale) as uint));
+                requests.push(BufferRequest(screen_rect, slot.rect));
+            }
+        }
+        requests
+    }
+
+    /// Visits every slot whose row/column range overlaps `rect`, re-checking the exact
+    /// rect intersection (the grid range is a conservative superset) before calling `f`.
+    fn for_each_slot_mut(&mut self, rect: Rect<f32>, include_border: bool, f: |&mut QuadtreeTile<T>|) {
+        let (mut row_range, mut col_range) = self.grid_range(rect);
+        if include_border {
+            // A tile that only touches `rect`'s border lies one cell outside the grid range.
+            row_range = (if row_range.0 > 0 { row_range.0 - 1 } else { 0 },
+                         (row_range.1 + 1).min(self.rows.len()));
+            col_range = (if col_range.0 > 0 { col_range.0 - 1 } else { 0 },
+                         (col_range.1 + 1).min(self.cols));
+        }
+        for row in range(row_range.0, row_range.1) {
+            for col in range(col_range.0, col_range.1) {
+                let slot = &mut self.rows[row][col];
+                if rects_intersect(&slot.rect, &rect, include_border) {
+                    f(slot);
+                }
+            }
+        }
+    }
+
+    /// The half-open `(row_start, row_end)`/`(col_start, col_end)` ranges of grid cells
+    /// that could possibly intersect `rect`, clamped to this quadtree's bounds.
+    fn grid_range(&self, rect: Rect<f32>) -> ((uint, uint), (uint, uint)) {
+        let tile_size = self.max_tile_size as f32;
+        let num_rows = self.rows.len();
+
+        let row_start = (rect.origin.y / tile_size).max(0.0) as uint;
+        let row_end = ((rect.origin.y + rect.size.height) / tile_size).ceil() as uint;
+        let col_start = (rect.origin.x / tile_size).max(0.0) as uint;
+        let col_end = ((rect.origin.x + rect.size.width) / tile_size).ceil() as uint;
+
+        ((row_start.min(num_rows), row_end.min(num_rows)),
+         (col_start.min(self.cols), col_end.min(self.cols)))
+    }
+}
+
+fn rects_intersect(a: &Rect<f32>, b: &Rect<f32>, touching_counts: bool) -> bool {
+    let (a_x0, a_x1) = (a.origin.x, a.origin.x + a.size.width);
+    let (a_y0, a_y1) = (a.origin.y, a.origin.y + a.size.height);
+    let (b_x0, b_x1) = (b.origin.x, b.origin.x + b.size.width);
+    let (b_y0, b_y1) = (b.origin.y, b.origin.y + b.size.height);
+    if touching_counts {
+        a_x0 <= b_x1 && b_x0 <= a_x1 && a_y0 <= b_y1 && b_y0 <= a_y1
+    } else {
+        a_x0 < b_x1 && b_x0 < a_x1 && a_y0 < b_y1 && b_y0 < a_y1
+    }
+}
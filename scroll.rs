@@ -0,0 +1,170 @@
+// Copyright 2013 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Turns the `scroll_offset`/`scroll_policy`/`wants_scroll_events` fields already on
+//! `CompositorLayer` into working nested scrolling: hit-testing a cursor point down the
+//! layer tree, clamping the scrolled layer's offset, and re-pinning `FixedPosition`
+//! descendants so they don't appear to move with the scrolled content.
+
+use layers::{Layer, ContainerLayer, CompositorLayer, PagePx};
+use layers::{ContainerLayerKind, CompositorLayerKind, TextureLayerKind, SolidColorLayerKind};
+use layers::{Scrollable, FixedPosition};
+use layers::{WantsScrollEvents, DoesntWantScrollEvents};
+
+use geom::matrix::Matrix4;
+use geom::point::{Point2D, TypedPoint2D};
+use geom::size::Size2D;
+
+/// Attempts to scroll `layer`'s subtree by `delta` (in page pixels) at `cursor`.
+/// Descends to the deepest `Scrollable` layer under `cursor` that wants scroll events,
+/// clamps its new `scroll_offset` against `page_size`/`viewport_size`, and counter-
+/// translates any `FixedPosition` descendants so they stay pinned. Returns whether some
+/// layer consumed the scroll; the caller should bubble an unconsumed scroll to whatever
+/// layer contains this one.
+pub fn handle_scroll(layer: &Layer,
+                      cursor: TypedPoint2D<PagePx, f32>,
+                      delta: TypedPoint2D<PagePx, f32>,
+                      viewport_size: Size2D<f32>)
+                      -> bool {
+    match *layer {
+        CompositorLayerKind(ref compositor) => {
+            if !container_contains_point(&compositor.container_layer, cursor) {
+                return false;
+            }
+
+            for child in compositor.container_layer.children() {
+                if handle_scroll(&child, cursor, delta, viewport_size) {
+                    return true;
+                }
+            }
+
+            if compositor.scroll_policy != Scrollable ||
+               compositor.wants_scroll_events != WantsScrollEvents {
+                return false;
+            }
+
+            match compositor.page_size {
+                None => false,
+                Some(page_size) => {
+                    scroll_compositor_layer(&**compositor, delta, page_size, viewport_size);
+                    true
+                }
+            }
+        }
+        ContainerLayerKind(ref container) => {
+            if !container_contains_point(&**container, cursor) {
+                return false;
+            }
+            for child in container.children() {
+                if handle_scroll(&child, cursor, delta, viewport_size) {
+                    return true;
+                }
+            }
+            false
+        }
+        TextureLayerKind(..) | SolidColorLayerKind(..) => false,
+    }
+}
+
+/// A layer with no `scissor` clip rect is treated as unbounded, since this tree has no
+/// other notion of a container layer's frame in its parent's coordinate space.
+fn container_contains_point(container: &ContainerLayer, point: TypedPoint2D<PagePx, f32>) -> bool {
+    match *container.scissor.borrow() {
+        Some(ref rect) => rect.contains(&Point2D(point.x, point.y)),
+        None => true,
+    }
+}
+
+fn scroll_compositor_layer(compositor: &CompositorLayer,
+                            delta: TypedPoint2D<PagePx, f32>,
+                            page_size: Size2D<f32>,
+                            viewport_size: Size2D<f32>) {
+    let max_x = (page_size.width - viewport_size.width).max(0.0);
+    let max_y = (page_size.height - viewport_size.height).max(0.0);
+
+    let mut offset = compositor.scroll_offset.borrow_mut();
+    let old_offset = *offset;
+    let new_x = (offset.x - delta.x).max(0.0).min(max_x);
+    let new_y = (offset.y - delta.y).max(0.0).min(max_y);
+    let new_offset = TypedPoint2D::new(new_x, new_y);
+    *offset = new_offset;
+
+    pin_fixed_descendants(&compositor.container_layer, counter_translation(old_offset, new_offset));
+}
+
+/// Returns the translation a `FixedPosition` descendant needs in order to stay put on
+/// screen after `scroll_offset` changes from `old` to `new`. Ordinary scrolled content
+/// sits at `screen = page - offset`, so it shifts on screen by `old - new` when the
+/// offset changes; a fixed layer inherits that same shift from its scrollable ancestor's
+/// cascading transform, so it must apply the opposite translation, `new - old`, to cancel
+/// it out.
+fn counter_translation(old: TypedPoint2D<PagePx, f32>,
+                        new: TypedPoint2D<PagePx, f32>)
+                        -> TypedPoint2D<PagePx, f32> {
+    TypedPoint2D::new(new.x - old.x, new.y - old.y)
+}
+
+/// Counter-translates every `FixedPosition` descendant of `container` by `delta` so it
+/// keeps its position on screen even though its scrollable ancestor's content just moved.
+/// Recurses through `Scrollable` descendants too, since a nested scrollable layer's own
+/// fixed-position children are still pinned to this ancestor's viewport.
+fn pin_fixed_descendants(container: &ContainerLayer, delta: TypedPoint2D<PagePx, f32>) {
+    for child in container.children() {
+        match child {
+            CompositorLayerKind(ref compositor) => {
+                if compositor.scroll_policy == FixedPosition {
+                    translate_layer(&child, delta);
+                } else {
+                    pin_fixed_descendants(&compositor.container_layer, delta);
+                }
+            }
+            ContainerLayerKind(ref inner) => pin_fixed_descendants(&**inner, delta),
+            TextureLayerKind(..) | SolidColorLayerKind(..) => {}
+        }
+    }
+}
+
+fn translate_layer(layer: &Layer, delta: TypedPoint2D<PagePx, f32>) {
+    layer.with_common(|common| {
+        common.transform = translation_matrix(delta.x, delta.y).mul_m(&common.transform);
+    });
+}
+
+fn translation_matrix(dx: f32, dy: f32) -> Matrix4<f32> {
+    Matrix4::new(1.0, 0.0, 0.0, 0.0,
+                 0.0, 1.0, 0.0, 0.0,
+                 0.0, 0.0, 1.0, 0.0,
+                 dx,  dy,  0.0, 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::counter_translation;
+    use layers::PagePx;
+    use geom::point::TypedPoint2D;
+
+    #[test]
+    fn counter_translation_cancels_the_inherited_scroll_shift() {
+        let old_offset: TypedPoint2D<PagePx, f32> = TypedPoint2D::new(0.0, 0.0);
+        let new_offset: TypedPoint2D<PagePx, f32> = TypedPoint2D::new(0.0, 40.0);
+
+        // Ordinary scrolled content sits at `screen = page - offset`, so scrolling the
+        // offset from 0 to 40 moves it by (0, -40) on screen.
+        let content_shift = (old_offset.x - new_offset.x, old_offset.y - new_offset.y);
+        assert_eq!(content_shift, (0.0, -40.0));
+
+        // A fixed-position descendant inherits that same (0, -40) shift from its
+        // scrollable ancestor, so it must counter-translate by the opposite amount to
+        // stay put on screen.
+        let counter = counter_translation(old_offset, new_offset);
+        assert_eq!((counter.x, counter.y), (0.0, 40.0));
+        assert_eq!(counter.x, -content_shift.0);
+        assert_eq!(counter.y, -content_shift.1);
+    }
+}
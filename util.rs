@@ -28,25 +28,6 @@ pub fn BufferRequest(screen_rect: Rect<uint>, page_rect: Rect<f32>) -> BufferReq
 }
 
 
-pub fn convert_rgb32_to_rgb24(buffer: &[u8]) -> Vec<u8> {
-    let mut i = 0;
-    Vec::from_fn(buffer.len() * 3 / 4, |j| {
-        match j % 3 {
-            0 => {
-                buffer[i + 2]
-            }
-            1 => {
-                buffer[i + 1]
-            }
-            2 => {
-                let val = buffer[i];
-                i += 4;
-                val
-            }
-            _ => {
-                fail!()
-            }
-        }
-    })
-}
+// Pixel-format conversion lives in the `pixels` module, keyed off the `Format` enum,
+// rather than as a single hardcoded BGRA -> RGB swizzle here.
 